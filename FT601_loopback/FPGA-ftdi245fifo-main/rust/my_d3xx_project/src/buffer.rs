@@ -0,0 +1,108 @@
+// Page-aligned read buffers and a pool to recycle them.
+//
+// At 400 MB/s a fresh `vec![0; chunk_size]` per read costs a large allocation plus page faults
+// for every chunk. Instead a fixed set of buffers is allocated up front and passed between the
+// reading thread and whoever consumes the data, then handed back to the pool.
+
+use std::alloc::{Layout, alloc_zeroed, dealloc, handle_alloc_error};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::mpsc::{Receiver, Sender, channel};
+
+const PAGE_SIZE: usize = 4096;
+/// Maximum packet size of the FT60x SuperSpeed bulk endpoints. Reads that are a whole number
+/// of packets never end in a short packet the driver has to split.
+pub const USB_PACKET_SIZE: usize = 1024;
+
+/// A zero-initialised, page-aligned heap buffer.
+pub struct AlignedBuffer {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl AlignedBuffer {
+    /// Allocate a buffer of at least `len` bytes, rounded up to a whole number of pages.
+    pub fn new(len: usize) -> Self {
+        let size = len.max(1).next_multiple_of(PAGE_SIZE);
+        let layout = Layout::from_size_align(size, PAGE_SIZE).expect("buffer size overflows");
+        // SAFETY: the layout has a non-zero size.
+        let ptr = unsafe { alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| handle_alloc_error(layout));
+        Self { ptr, layout }
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: ptr points to layout.size() initialised bytes owned by self.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as above, and &mut self guarantees exclusive access.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        // SAFETY: allocated in new() with the same layout.
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+// SAFETY: the buffer uniquely owns its allocation, like a Vec<u8>.
+unsafe impl Send for AlignedBuffer {}
+
+/// A fixed set of equally sized buffers.
+///
+/// Taking a buffer blocks until one is free, which also bounds how far a reader can run ahead
+/// of a slow consumer. Consumers hand buffers back through a [`Recycler`].
+pub struct BufferPool {
+    free_tx: Sender<AlignedBuffer>,
+    free_rx: Receiver<AlignedBuffer>,
+}
+
+/// Returns buffers to the pool they came from; may be sent to another thread.
+#[derive(Clone)]
+pub struct Recycler(Sender<AlignedBuffer>);
+
+impl BufferPool {
+    /// Allocate `count` buffers of `size` bytes, rounded up to whole USB packets.
+    pub fn new(count: usize, size: usize) -> Self {
+        let size = size.max(1).next_multiple_of(USB_PACKET_SIZE);
+        let (free_tx, free_rx) = channel();
+        for _ in 0..count.max(1) {
+            free_tx
+                .send(AlignedBuffer::new(size))
+                .expect("pool receiver is alive");
+        }
+        Self { free_tx, free_rx }
+    }
+
+    /// Take a free buffer, waiting for one to be recycled if necessary.
+    pub fn take(&self) -> AlignedBuffer {
+        // The pool holds a sender itself, so the channel can never be disconnected.
+        self.free_rx.recv().expect("pool sender is alive")
+    }
+
+    /// Put a buffer back without going through a recycler.
+    pub fn give(&self, buffer: AlignedBuffer) {
+        self.free_tx.send(buffer).expect("pool receiver is alive");
+    }
+
+    pub fn recycler(&self) -> Recycler {
+        Recycler(self.free_tx.clone())
+    }
+}
+
+impl Recycler {
+    pub fn recycle(&self, buffer: AlignedBuffer) {
+        // If the pool is already gone the buffer is simply freed.
+        let _ = self.0.send(buffer);
+    }
+}
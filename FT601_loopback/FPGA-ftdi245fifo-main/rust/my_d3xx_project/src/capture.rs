@@ -0,0 +1,906 @@
+// Streaming capture from the FPGA to a file.
+//
+// Each request writes the 4-byte little-endian byte count to an OUT pipe (Out0 by default),
+// after which the FPGA sends that many bytes on the matching IN pipe. Requests repeat until
+// the requested count is reached (or forever), so this is the mode to use for long
+// acquisition runs.
+//
+// Several IN pipes of a device, and several devices, can be captured at once, each stream on
+// its own thread with its own output.
+// The threads are released together by a shared trigger and report their start and first
+// data times relative to it, so the captures can be aligned afterwards.
+//
+// Reads go into buffers from a `BufferPool` and are handed to a writer thread, so file I/O
+// overlaps the next USB transfer and nothing is allocated or copied per chunk.
+//
+// An optional watchdog reports streams that stop delivering data, and can abort the pipe and
+// either resume or stop with what was captured.
+//
+// All progress and diagnostics go to stderr so that with `-o -` the data can be piped from
+// stdout into other programs.
+
+use std::fs::File;
+use std::io::{BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Sender, channel};
+use std::sync::{Arc, Barrier};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use d3xx::{D3xxError, Device, Pipe};
+
+use crate::buffer::{AlignedBuffer, BufferPool, Recycler};
+use crate::stats::Stats;
+use crate::watchdog::Watchdog;
+use crate::{Result, parse_value};
+
+/// Written to the output where data was lost during a reconnection:
+/// the magic, then the wall-clock time in ms since the Unix epoch and the number of bytes
+/// captured before the gap, both as little-endian u64.
+const GAP_MARKER_MAGIC: &[u8; 8] = b"FT60xGAP";
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// With a watchdog the IN pipe timeout is set this much longer than the watchdog period, so a
+/// stall is always seen by the watchdog before the driver gives up on the read.
+const STALL_TIMEOUT_MARGIN: Duration = Duration::from_secs(1);
+
+pub struct CaptureOptions {
+    pub bytes_per_request: u32,
+    /// Number of requests to make, or `None` to run until interrupted.
+    pub requests: Option<u64>,
+    pub chunk_size: usize,
+    /// Number of chunk buffers shared between the reader and the writer thread.
+    pub buffers: usize,
+    pub output: Option<PathBuf>,
+    pub reconnect: bool,
+    pub reconnect_attempts: u32,
+    /// Print sample statistics this often while capturing, and in full at the end.
+    pub stats_interval: Option<Duration>,
+    /// (IN, OUT) pipe pairs. Each IN pipe is captured concurrently to its own output, with
+    /// its requests sent on the paired OUT pipe.
+    pub pipes: Vec<(Pipe, Pipe)>,
+    /// Report a stall when a stream makes no progress for this long.
+    pub watchdog: Option<Duration>,
+    /// Abort the IN pipe on a stall so the blocked read gives up immediately.
+    pub stall_abort: bool,
+    pub on_stall: OnStall,
+}
+
+/// What to do once a stall has ended the current transfer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OnStall {
+    /// Mark a gap in the output and re-request what is still missing.
+    Continue,
+    /// Stop the stream, keeping what was captured.
+    Exit,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self {
+            bytes_per_request: 1_000_000_000,
+            requests: Some(1),
+            chunk_size: 16 * 1024 * 1024,
+            buffers: 8,
+            output: None,
+            reconnect: false,
+            reconnect_attempts: 30,
+            stats_interval: None,
+            pipes: vec![(Pipe::In0, Pipe::Out0)],
+            watchdog: None,
+            stall_abort: false,
+            on_stall: OnStall::Exit,
+        }
+    }
+}
+
+impl CaptureOptions {
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let mut options = Self::default();
+        let mut in_pipes = vec![0];
+        let mut out_pipes = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bytes" => options.bytes_per_request = parse_value(arg, args.next())?,
+                "--requests" => options.requests = Some(parse_value(arg, args.next())?),
+                "--forever" => options.requests = None,
+                "--chunk" => options.chunk_size = parse_value(arg, args.next())?,
+                "--buffers" => options.buffers = parse_value(arg, args.next())?,
+                "--output" | "-o" => options.output = Some(parse_value(arg, args.next())?),
+                "--reconnect" => options.reconnect = true,
+                "--reconnect-attempts" => {
+                    options.reconnect = true;
+                    options.reconnect_attempts = parse_value(arg, args.next())?;
+                }
+                "--stats" => options.stats_interval = Some(Duration::from_secs(10)),
+                "--stats-interval" => {
+                    options.stats_interval = Some(parse_seconds(arg, args.next())?);
+                }
+                "--in-pipe" => in_pipes = parse_pipe_list(arg, args.next())?,
+                "--out-pipe" => out_pipes = parse_pipe_list(arg, args.next())?,
+                "--watchdog" => options.watchdog = Some(parse_seconds(arg, args.next())?),
+                "--stall-abort" => options.stall_abort = true,
+                "--on-stall" => {
+                    let action: String = parse_value(arg, args.next())?;
+                    options.on_stall = match action.as_str() {
+                        "continue" => OnStall::Continue,
+                        "exit" => OnStall::Exit,
+                        _ => return Err("--on-stall must be continue or exit".into()),
+                    };
+                }
+                other => return Err(format!("unknown capture option '{other}'").into()),
+            }
+        }
+        if options.bytes_per_request == 0 || options.chunk_size == 0 || options.buffers == 0 {
+            return Err("--bytes, --chunk and --buffers must be non-zero".into());
+        }
+        if options.watchdog == Some(Duration::ZERO) {
+            return Err("--watchdog must be non-zero".into());
+        }
+        // No read asks for more than one request, so a larger chunk would only waste memory.
+        options.chunk_size = options.chunk_size.min(options.bytes_per_request as usize);
+
+        // By default each IN pipe is requested through the OUT pipe of the same channel.
+        // Streams run concurrently, so they can't share an OUT pipe (the list has no repeats).
+        let out_pipes = match out_pipes.len() {
+            0 => in_pipes.clone(),
+            n if n == in_pipes.len() => out_pipes,
+            _ => return Err("--out-pipe needs one distinct pipe per --in-pipe".into()),
+        };
+        options.pipes = in_pipes
+            .iter()
+            .zip(&out_pipes)
+            .map(|(&in_index, &out_index)| (in_pipe(in_index), out_pipe(out_index)))
+            .collect();
+        if options.pipes.len() > 1 && options.reconnect {
+            return Err("--reconnect is only supported when capturing a single IN pipe".into());
+        }
+        if options.watchdog.is_none() && (options.stall_abort || options.on_stall != OnStall::Exit)
+        {
+            return Err("--stall-abort and --on-stall need --watchdog".into());
+        }
+        Ok(options)
+    }
+}
+
+/// Parse a duration in seconds, which may be fractional.
+fn parse_seconds(flag: &str, value: Option<&String>) -> Result<Duration> {
+    let seconds: f64 = parse_value(flag, value)?;
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|e| format!("invalid value '{seconds}' for {flag}: {e}").into())
+}
+
+/// Parse a comma-separated list of pipe numbers 0..=3.
+fn parse_pipe_list(flag: &str, value: Option<&String>) -> Result<Vec<u8>> {
+    let value: String = parse_value(flag, value)?;
+    let mut pipes = Vec::new();
+    for item in value.split(',') {
+        match item.parse::<u8>() {
+            Ok(pipe) if pipe < 4 && !pipes.contains(&pipe) => pipes.push(pipe),
+            _ => {
+                return Err(
+                    format!("{flag} takes distinct pipe numbers 0-3, got '{value}'").into(),
+                );
+            }
+        }
+    }
+    Ok(pipes)
+}
+
+pub fn in_pipe(index: u8) -> Pipe {
+    Pipe::try_from(u8::from(Pipe::In0) + index).expect("pipe index checked when parsing")
+}
+
+pub fn out_pipe(index: u8) -> Pipe {
+    Pipe::try_from(u8::from(Pipe::Out0) + index).expect("pipe index checked when parsing")
+}
+
+fn pipe_index(pipe: Pipe) -> u8 {
+    if pipe.is_in() {
+        u8::from(pipe) - u8::from(Pipe::In0)
+    } else {
+        u8::from(pipe) - u8::from(Pipe::Out0)
+    }
+}
+
+/// Where a stream has got to; survives reconnections.
+#[derive(Default)]
+struct Progress {
+    requests_done: u64,
+    /// Bytes still owed by the current request, or 0 if a new request is needed.
+    remaining: usize,
+    total_bytes: u64,
+    first_data: Option<Instant>,
+}
+
+/// What the reader hands to the writer thread.
+enum Block {
+    /// A pool buffer with this many valid bytes at the start.
+    Data(AlignedBuffer, usize),
+    /// Data was lost here; `total_bytes` were captured before the gap.
+    Gap { total_bytes: u64 },
+}
+
+/// The output for one IN pipe, opened before the capture starts.
+struct StreamOutput {
+    in_pipe: Pipe,
+    out_pipe: Pipe,
+    label: String,
+    output: Box<dyn Write + Send>,
+}
+
+/// One IN pipe being captured, with the OUT pipe its requests are sent on.
+struct Stream {
+    in_pipe: Pipe,
+    out_pipe: Pipe,
+    label: String,
+    pool: BufferPool,
+    writer: Writer,
+    progress: Progress,
+    watchdog: Option<Watchdog>,
+    start: Instant,
+}
+
+impl Stream {
+    /// Allocate the buffers and start the helper threads, ready for `begin`.
+    fn new(output: StreamOutput, options: &CaptureOptions) -> Self {
+        let StreamOutput {
+            in_pipe,
+            out_pipe,
+            label,
+            output,
+        } = output;
+        let pool = BufferPool::new(options.buffers, options.chunk_size);
+        let writer = Writer::spawn(
+            output,
+            pool.recycler(),
+            options.stats_interval,
+            label.clone(),
+        );
+        let watchdog = options
+            .watchdog
+            .map(|timeout| Watchdog::spawn(label.clone(), timeout, options.stall_abort));
+        Self {
+            in_pipe,
+            out_pipe,
+            label,
+            pool,
+            writer,
+            progress: Progress::default(),
+            watchdog,
+            start: Instant::now(),
+        }
+    }
+
+    /// Mark the start of the capture, just before the first request.
+    fn begin(&mut self, epoch: Instant) {
+        self.start = Instant::now();
+        let start_unix_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        eprintln!(
+            "{}Capture started at {start_unix_ms} ms since the Unix epoch (trigger +{:?})",
+            self.label,
+            self.start - epoch
+        );
+    }
+
+    /// Stop the writer and print the summary, given how the capture itself went.
+    fn finish(self, result: Result<()>, epoch: Instant) -> Result<()> {
+        if let Some(watchdog) = self.watchdog {
+            watchdog.stop();
+        }
+        // A stopped writer makes the reader fail too; its own error is the useful one.
+        self.writer.finish()?;
+        result?;
+
+        let label = &self.label;
+        let duration = self.start.elapsed();
+        eprintln!("{label}Total bytes read: {}", self.progress.total_bytes);
+        if let Some(first_data) = self.progress.first_data {
+            eprintln!("{label}First data at trigger +{:?}", first_data - epoch);
+        }
+        eprintln!(
+            "{label}Time taken: {:?}, {} MB/s",
+            duration,
+            self.progress.total_bytes as f64 / 1e6 / duration.as_secs_f64()
+        );
+        Ok(())
+    }
+}
+
+/// Lets several threads read different pipes of one device.
+///
+/// d3xx makes `Device` `!Sync` because it doesn't trust the driver with concurrent calls in
+/// general, but D3XX does support concurrent calls on different pipes: transfers, per-pipe
+/// settings such as the pipe timeout, and `FT_AbortPipe`, which is meant to be called from
+/// another thread to cancel a transfer blocked on the pipe. That is all the multi-pipe
+/// threads and their watchdogs do with it.
+struct SharedDevice(Device);
+
+// SAFETY: see above. Each stream thread, and its watchdog thread, only uses the stream's own
+// IN and OUT pipe (`CaptureOptions::parse` rejects a pipe shared by two streams). Nothing
+// changes device-wide settings or closes the device while they run.
+unsafe impl Sync for SharedDevice {}
+
+pub fn run(serials: &[String], options: &CaptureOptions) -> Result<()> {
+    let multi_device = serials.len() > 1;
+    let multi_pipe = options.pipes.len() > 1;
+    // Open every device and output up front, so a missing board fails before any capture starts,
+    // and set up the streams so the trigger isn't delayed by allocating their buffers.
+    let mut jobs = Vec::new();
+    for serial in serials {
+        let mut streams = Vec::new();
+        for &(in_pipe, out_pipe) in &options.pipes {
+            let pipe_name = format!("in{}", pipe_index(in_pipe));
+            let mut substitutions = Vec::new();
+            if multi_device {
+                substitutions.push(("serial", serial.as_str()));
+            }
+            if multi_pipe {
+                substitutions.push(("pipe", pipe_name.as_str()));
+            }
+            let label = match substitutions.as_slice() {
+                [] => String::new(),
+                parts => {
+                    let values: Vec<_> = parts.iter().map(|(_, value)| *value).collect();
+                    format!("[{}] ", values.join(" "))
+                }
+            };
+            let output = open_output(options.output.as_deref(), &substitutions)?;
+            let output = StreamOutput {
+                in_pipe,
+                out_pipe,
+                label,
+                output,
+            };
+            streams.push(Stream::new(output, options));
+        }
+        jobs.push((serial.as_str(), Device::open(serial)?, streams));
+    }
+
+    // Every stream waits here and then sends its first request together with the others.
+    let trigger = Barrier::new(jobs.iter().map(|(_, _, streams)| streams.len()).sum());
+    let epoch = Instant::now();
+    let all_ok = std::thread::scope(|scope| {
+        let threads: Vec<_> = jobs
+            .into_iter()
+            .map(|(serial, device, streams)| {
+                let trigger = &trigger;
+                scope
+                    .spawn(move || capture_device(serial, device, streams, options, trigger, epoch))
+            })
+            .collect();
+        let mut all_ok = true;
+        for thread in threads {
+            all_ok &= thread.join().expect("capture thread panicked");
+        }
+        all_ok
+    });
+    if all_ok {
+        Ok(())
+    } else {
+        Err("capture failed".into())
+    }
+}
+
+/// Open the output for one stream.
+///
+/// When several devices or pipes are captured each stream gets its own file: `{serial}` and
+/// `{pipe}` in the path are replaced by the serial number and IN pipe name, and any that
+/// aren't used in the path are appended to the file stem instead.
+fn open_output(
+    path: Option<&Path>,
+    substitutions: &[(&str, &str)],
+) -> Result<Box<dyn Write + Send>> {
+    let Some(path) = path else {
+        return Ok(Box::new(std::io::sink()));
+    };
+    if path.as_os_str() == "-" {
+        if !substitutions.is_empty() {
+            return Err("stdout output is only supported when capturing a single stream".into());
+        }
+        if std::io::stdout().is_terminal() {
+            return Err("refusing to write binary data to a terminal, redirect stdout".into());
+        }
+        return Ok(Box::new(std::io::stdout()));
+    }
+    let path = output_path(path, substitutions);
+    Ok(Box::new(BufWriter::new(File::create(path)?)))
+}
+
+/// The file name for one stream, with the substitutions described at `open_output`.
+fn output_path(path: &Path, substitutions: &[(&str, &str)]) -> PathBuf {
+    let mut template = path.to_string_lossy().into_owned();
+    let mut suffix = String::new();
+    for (key, value) in substitutions {
+        let placeholder = format!("{{{key}}}");
+        if template.contains(&placeholder) {
+            template = template.replace(&placeholder, value);
+        } else {
+            suffix.push('_');
+            suffix.push_str(value);
+        }
+    }
+    let mut path = PathBuf::from(template);
+    if !suffix.is_empty() {
+        let mut name = path.file_stem().unwrap_or_default().to_os_string();
+        name.push(suffix);
+        if let Some(extension) = path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        path.set_file_name(name);
+    }
+    path
+}
+
+/// Capture all streams of one device until done, reporting errors as they happen.
+///
+/// `trigger` and `epoch` are shared by all streams of the run. Returns whether every stream
+/// succeeded.
+fn capture_device(
+    serial: &str,
+    device: Device,
+    mut streams: Vec<Stream>,
+    options: &CaptureOptions,
+    trigger: &Barrier,
+    epoch: Instant,
+) -> bool {
+    let report = |label: &str, result: Result<()>| match result {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{label}Error: {e}");
+            false
+        }
+    };
+
+    if streams.len() == 1 {
+        // A single stream owns the device, so it can also reconnect it.
+        let mut stream = streams.remove(0);
+        trigger.wait();
+        stream.begin(epoch);
+        let result = capture(serial, device, &mut stream, options);
+        let label = stream.label.clone();
+        return report(&label, stream.finish(result, epoch));
+    }
+
+    let device = SharedDevice(device);
+    std::thread::scope(|scope| {
+        let threads: Vec<_> = streams
+            .into_iter()
+            .map(|mut stream| {
+                let device = &device;
+                scope.spawn(move || {
+                    trigger.wait();
+                    stream.begin(epoch);
+                    let result = match stream_session(&device.0, &mut stream, options) {
+                        Ok(Ok(())) => Ok(()),
+                        Ok(Err(e)) => Err(format!(
+                            "device error after {} bytes: {e}",
+                            stream.progress.total_bytes
+                        )
+                        .into()),
+                        Err(e) => Err(e),
+                    };
+                    let label = stream.label.clone();
+                    report(&label, stream.finish(result, epoch))
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .all(|thread| thread.join().expect("capture thread panicked"))
+    })
+}
+
+/// Run sessions until done, reconnecting between them if enabled.
+fn capture(
+    serial: &str,
+    mut device: Device,
+    stream: &mut Stream,
+    options: &CaptureOptions,
+) -> Result<()> {
+    let label = stream.label.clone();
+    loop {
+        match stream_session(&device, stream, options)? {
+            Ok(()) => return Ok(()),
+            Err(e) if options.reconnect => {
+                let total_bytes = stream.progress.total_bytes;
+                eprintln!(
+                    "\n{label}Device error after {total_bytes} bytes: {e}. Reconnecting to {serial}..."
+                );
+                // Close the old handle before re-opening, the driver won't open a device twice.
+                drop(device);
+                stream.writer.send(Block::Gap { total_bytes })?;
+                device = reconnect(serial, &label, options.reconnect_attempts)?;
+                eprintln!("{label}Reconnected, resuming capture.");
+            }
+            Err(e) => {
+                return Err(format!(
+                    "device error after {} bytes: {e}",
+                    stream.progress.total_bytes
+                )
+                .into());
+            }
+        }
+    }
+}
+
+/// Run sessions on one device handle, restarting after a stall if asked to.
+///
+/// Errors are as for `session`. A device error caused by a stall, meaning a read that was
+/// aborted by the watchdog or that timed out, is handled here; any other is passed on to the
+/// caller.
+fn stream_session(
+    device: &Device,
+    stream: &mut Stream,
+    options: &CaptureOptions,
+) -> Result<std::io::Result<()>> {
+    if let Some(timeout) = options.watchdog {
+        let pipe_timeout_ms = (timeout + STALL_TIMEOUT_MARGIN).as_millis();
+        let pipe_timeout_ms = u32::try_from(pipe_timeout_ms).unwrap_or(u32::MAX);
+        if let Err(e) = device.pipe(stream.in_pipe).set_timeout(pipe_timeout_ms) {
+            return Ok(Err(e.into()));
+        }
+    }
+    loop {
+        if let Some(watchdog) = &stream.watchdog {
+            watchdog.arm(device, stream.in_pipe);
+        }
+        let result = session(device, stream, options);
+        if let Some(watchdog) = &stream.watchdog {
+            watchdog.disarm();
+        }
+        match result? {
+            // Always take the flag, so a stale one can't affect the next session.
+            Err(e)
+                if stream
+                    .watchdog
+                    .as_ref()
+                    .is_some_and(|watchdog| watchdog.take_stall() | is_timeout(&e)) =>
+            {
+                let label = &stream.label;
+                let total_bytes = stream.progress.total_bytes;
+                if options.on_stall == OnStall::Exit {
+                    eprintln!("{label}Stopping after stall ({e}), keeping {total_bytes} bytes.");
+                    return Ok(Ok(()));
+                }
+                eprintln!("{label}Stall ended the transfer ({e}), re-requesting.");
+                // Whatever the aborted read had received is lost.
+                stream.writer.send(Block::Gap { total_bytes })?;
+            }
+            result => return Ok(result),
+        }
+    }
+}
+
+/// Issue requests and pass the data to the writer until all requests are done.
+///
+/// The outer error is fatal (the writer has stopped); the inner one is a device error the
+/// caller may recover from by reconnecting. The stream's progress is kept up to date so a
+/// resumed session re-requests only what is still missing from the current request.
+fn session(
+    device: &Device,
+    stream: &mut Stream,
+    options: &CaptureOptions,
+) -> Result<std::io::Result<()>> {
+    let Stream {
+        in_pipe,
+        out_pipe,
+        label,
+        pool,
+        writer,
+        progress,
+        watchdog,
+        ..
+    } = stream;
+    // The watchdog only times the IN pipe reads themselves, not the request writes or waits
+    // for a free buffer.
+    let watchdog = watchdog.as_ref();
+    let begin = || {
+        if let Some(watchdog) = watchdog {
+            watchdog.begin();
+        }
+    };
+    let end = |bytes| {
+        if let Some(watchdog) = watchdog {
+            watchdog.end(bytes);
+        }
+    };
+    while options.requests.is_none_or(|n| progress.requests_done < n) {
+        if progress.remaining == 0 {
+            progress.remaining = options.bytes_per_request as usize;
+        }
+        // The remainder always fits since it never exceeds bytes_per_request.
+        let request = progress.remaining as u32;
+        if let Err(e) = device.pipe(*out_pipe).write_all(&request.to_le_bytes()) {
+            return Ok(Err(e));
+        }
+
+        while progress.remaining > 0 {
+            let mut buffer = pool.take();
+            let chunk_size = std::cmp::min(buffer.len(), progress.remaining);
+            begin();
+            let read = device.pipe(*in_pipe).read(&mut buffer[..chunk_size]);
+            end(*read.as_ref().unwrap_or(&0));
+            let bytes_in_chunk = match read {
+                Ok(n) => n,
+                // A timeout means the FPGA has no more data for this request, unless a watchdog
+                // is watching for stalls; then it is left to `stream_session`.
+                Err(e) if is_timeout(&e) && watchdog.is_none() => 0,
+                Err(e) => {
+                    pool.give(buffer);
+                    return Ok(Err(e));
+                }
+            };
+            if bytes_in_chunk == 0 {
+                // The read timed out; the FPGA has no more data for this request.
+                pool.give(buffer);
+                eprintln!(
+                    "\n{label}Request {} finished early with {} bytes missing.",
+                    progress.requests_done, progress.remaining
+                );
+                progress.remaining = 0;
+                break;
+            }
+            progress.first_data.get_or_insert_with(Instant::now);
+            writer.send(Block::Data(buffer, bytes_in_chunk))?;
+            progress.remaining -= bytes_in_chunk;
+            progress.total_bytes += bytes_in_chunk as u64;
+        }
+        progress.requests_done += 1;
+    }
+    Ok(Ok(()))
+}
+
+/// Whether a pipe transfer failed only because the driver's pipe timeout expired.
+fn is_timeout(error: &std::io::Error) -> bool {
+    error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<D3xxError>())
+        == Some(&D3xxError::Timeout)
+}
+
+/// The thread writing captured blocks to the output.
+///
+/// After a write error the thread keeps recycling buffers (so the reader can never starve
+/// waiting on the pool) and flags the failure, which makes the next `send` fail.
+/// Statistics are computed here too, keeping the reader free to service the pipe.
+struct Writer {
+    blocks: Sender<Block>,
+    failed: Arc<AtomicBool>,
+    thread: JoinHandle<std::io::Result<()>>,
+}
+
+impl Writer {
+    fn spawn(
+        mut output: Box<dyn Write + Send>,
+        recycler: Recycler,
+        stats_interval: Option<Duration>,
+        label: String,
+    ) -> Self {
+        let (blocks, block_rx) = channel();
+        let failed = Arc::new(AtomicBool::new(false));
+        let thread_failed = Arc::clone(&failed);
+        let thread = std::thread::spawn(move || {
+            let mut result = Ok(());
+            let mut stats = stats_interval.map(|_| Stats::default());
+            let mut last_report = Instant::now();
+            for block in block_rx {
+                let buffer = match block {
+                    Block::Data(buffer, len) => {
+                        if result.is_ok() {
+                            result = output.write_all(&buffer[..len]);
+                        }
+                        if let Some(stats) = &mut stats {
+                            stats.add(&buffer[..len]);
+                        }
+                        Some(buffer)
+                    }
+                    Block::Gap { total_bytes } => {
+                        if result.is_ok() {
+                            result = write_gap_marker(&mut output, total_bytes);
+                        }
+                        if let Some(stats) = &mut stats {
+                            stats.gap();
+                        }
+                        None
+                    }
+                };
+                if let (Some(stats), Some(interval)) = (&stats, stats_interval)
+                    && last_report.elapsed() >= interval
+                {
+                    eprintln!("{label}Stats: {}", stats.summary());
+                    last_report = Instant::now();
+                }
+                if result.is_err() {
+                    thread_failed.store(true, Ordering::Relaxed);
+                }
+                if let Some(buffer) = buffer {
+                    recycler.recycle(buffer);
+                }
+            }
+            if let Some(stats) = &stats {
+                eprintln!("{label}Stats: {}", stats.summary());
+                eprint!("{label}Byte histogram:\n{}", stats.histogram());
+            }
+            result.and_then(|()| output.flush())
+        });
+        Self {
+            blocks,
+            failed,
+            thread,
+        }
+    }
+
+    fn send(&self, block: Block) -> Result<()> {
+        if self.failed.load(Ordering::Relaxed) || self.blocks.send(block).is_err() {
+            return Err("output writer stopped".into());
+        }
+        Ok(())
+    }
+
+    /// Hang up, let the thread drain what is queued, and return its result.
+    fn finish(self) -> std::io::Result<()> {
+        drop(self.blocks);
+        self.thread.join().expect("writer thread panicked")
+    }
+}
+
+fn write_gap_marker(output: &mut dyn Write, total_bytes: u64) -> std::io::Result<()> {
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    output.write_all(GAP_MARKER_MAGIC)?;
+    output.write_all(&timestamp_ms.to_le_bytes())?;
+    output.write_all(&total_bytes.to_le_bytes())?;
+    Ok(())
+}
+
+/// Re-open the device by serial number once it has re-enumerated.
+fn reconnect(serial: &str, label: &str, attempts: u32) -> Result<Device> {
+    for attempt in 1..=attempts {
+        std::thread::sleep(RECONNECT_DELAY);
+        match Device::open(serial) {
+            Ok(device) => return Ok(device),
+            Err(e) => eprintln!("{label}Reconnect attempt {attempt}/{attempts} failed: {e}"),
+        }
+    }
+    Err(format!("could not reconnect to {serial} after {attempts} attempts").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<CaptureOptions> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        CaptureOptions::parse(&args)
+    }
+
+    fn parse_err(args: &str) -> String {
+        match parse(args) {
+            Ok(_) => panic!("'{args}' should be rejected"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn pipes(args: &str) -> Vec<(Pipe, Pipe)> {
+        match parse(args) {
+            Ok(options) => options.pipes,
+            Err(e) => panic!("'{args}': {e}"),
+        }
+    }
+
+    #[test]
+    fn out_pipes_default_to_same_channel() {
+        assert_eq!(pipes(""), [(Pipe::In0, Pipe::Out0)]);
+        assert_eq!(
+            pipes("--in-pipe 0,2"),
+            [(Pipe::In0, Pipe::Out0), (Pipe::In2, Pipe::Out2)]
+        );
+    }
+
+    #[test]
+    fn out_pipes_paired_in_order() {
+        assert_eq!(pipes("--in-pipe 3 --out-pipe 1"), [(Pipe::In3, Pipe::Out1)]);
+        assert_eq!(
+            pipes("--in-pipe 0,1 --out-pipe 1,0"),
+            [(Pipe::In0, Pipe::Out1), (Pipe::In1, Pipe::Out0)]
+        );
+    }
+
+    #[test]
+    fn mismatched_out_pipe_count() {
+        for args in ["--in-pipe 0,1 --out-pipe 0", "--out-pipe 0,1"] {
+            assert_eq!(
+                parse_err(args),
+                "--out-pipe needs one distinct pipe per --in-pipe"
+            );
+        }
+    }
+
+    #[test]
+    fn duplicate_or_invalid_pipe() {
+        assert_eq!(
+            parse_err("--in-pipe 1,1"),
+            "--in-pipe takes distinct pipe numbers 0-3, got '1,1'"
+        );
+        assert_eq!(
+            parse_err("--in-pipe 0,1 --out-pipe 2,2"),
+            "--out-pipe takes distinct pipe numbers 0-3, got '2,2'"
+        );
+        parse_err("--in-pipe 4");
+        parse_err("--in-pipe 0,");
+    }
+
+    #[test]
+    fn reconnect_needs_single_pipe() {
+        parse_err("--in-pipe 0,1 --reconnect");
+    }
+
+    #[test]
+    fn watchdog_period() {
+        match parse("--watchdog 0.5") {
+            Ok(options) => assert_eq!(options.watchdog, Some(Duration::from_millis(500))),
+            Err(e) => panic!("{e}"),
+        }
+        assert_eq!(parse_err("--watchdog 0"), "--watchdog must be non-zero");
+        for value in ["-1", "nan", "inf"] {
+            parse_err(&format!("--watchdog {value}"));
+        }
+        parse_err("--stall-abort");
+    }
+
+    #[test]
+    fn chunk_clamped_to_request() {
+        match parse("--bytes 1000 --chunk 18446744073709551615") {
+            Ok(options) => assert_eq!(options.chunk_size, 1000),
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    #[test]
+    fn output_path_placeholders_replaced() {
+        let substitutions = [("serial", "A1"), ("pipe", "in0")];
+        assert_eq!(
+            output_path(Path::new("cap_{serial}_{pipe}.bin"), &substitutions),
+            Path::new("cap_A1_in0.bin")
+        );
+        assert_eq!(
+            output_path(Path::new("{serial}/cap.bin"), &substitutions),
+            Path::new("A1/cap_in0.bin")
+        );
+    }
+
+    #[test]
+    fn output_path_values_appended_before_extension() {
+        assert_eq!(
+            output_path(
+                Path::new("out/cap.bin"),
+                &[("serial", "A1"), ("pipe", "in2")]
+            ),
+            Path::new("out/cap_A1_in2.bin")
+        );
+    }
+
+    #[test]
+    fn output_path_without_extension() {
+        assert_eq!(
+            output_path(Path::new("capture"), &[("serial", "A1")]),
+            Path::new("capture_A1")
+        );
+    }
+
+    #[test]
+    fn output_path_unchanged_for_single_stream() {
+        assert_eq!(
+            output_path(Path::new("data/cap.bin"), &[]),
+            Path::new("data/cap.bin")
+        );
+    }
+}
@@ -0,0 +1,292 @@
+// FT60x chip configuration commands.
+//
+// The d3xx crate can read the chip configuration but not write it, so both directions go
+// through the raw FT_GetChipConfiguration / FT_SetChipConfiguration calls on the device
+// handle. Field meanings follow AN_370 (FT60X Configuration Programmer User Guide).
+
+use d3xx::{D3xxError, Device, ffi};
+
+use crate::Result;
+
+// OptionalFeatureSupport bits. Bits 2..5 enable notification messages on IN pipes 0..3,
+// bits 6..9 disable "cancel session on underrun" on IN pipes 0..3.
+const FLAG_NOTIFICATION_ENABLE_PIPE0: u16 = 0b0000_0000_0100;
+const FLAG_UNDERRUN_DISABLE_PIPE0: u16 = 0b0000_0100_0000;
+const NUM_IN_PIPES: u16 = 4;
+
+const CHANNEL_CONFIG_NAMES: [&str; 5] = ["4", "2", "1", "1out", "1in"];
+const FIFO_MODE_245: u8 = 0;
+const FIFO_MODE_600: u8 = 1;
+const CHANNEL_CONFIG_ONE: u8 = 2;
+
+/// Entry point for `config <show|set|reset>`.
+pub fn run(device: &Device, args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        None | Some("show") => {
+            let config = read(device)?;
+            print(&config);
+        }
+        Some("set") => {
+            if args.len() < 2 {
+                return Err("config set needs at least one key=value setting".into());
+            }
+            let mut config = read(device)?;
+            for setting in &args[1..] {
+                apply_setting(&mut config, setting)?;
+            }
+            validate(&config)?;
+            write(device, Some(&mut config))?;
+            println!("New configuration written:");
+            print(&config);
+            println!(
+                "\nThe device re-enumerates after a configuration change; re-open it before use."
+            );
+        }
+        Some("reset") => {
+            // Passing NULL restores the factory default configuration.
+            write(device, None)?;
+            println!("Factory default configuration restored. The device will re-enumerate.");
+        }
+        Some(other) => return Err(format!("unknown config command '{other}'").into()),
+    }
+    Ok(())
+}
+
+/// Reject combinations the chip accepts but can't work with.
+fn validate(config: &ffi::FT_60XCONFIGURATION) -> Result<()> {
+    // 245 FIFO mode only has a single channel; the chip silently misbehaves otherwise.
+    if config.FIFOMode == FIFO_MODE_245 && config.ChannelConfig < CHANNEL_CONFIG_ONE {
+        return Err("245 FIFO mode supports only one channel (channels=1, 1out or 1in)".into());
+    }
+    Ok(())
+}
+
+fn check(status: ffi::FT_STATUS) -> Result<()> {
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(D3xxError::from(status).into())
+    }
+}
+
+fn read(device: &Device) -> Result<ffi::FT_60XCONFIGURATION> {
+    let mut config: ffi::FT_60XCONFIGURATION = unsafe { std::mem::zeroed() };
+    check(unsafe { ffi::FT_GetChipConfiguration(device.handle(), (&raw mut config).cast()) })?;
+    Ok(config)
+}
+
+fn write(device: &Device, config: Option<&mut ffi::FT_60XCONFIGURATION>) -> Result<()> {
+    let ptr = match config {
+        Some(config) => (config as *mut ffi::FT_60XCONFIGURATION).cast(),
+        None => std::ptr::null_mut(),
+    };
+    check(unsafe { ffi::FT_SetChipConfiguration(device.handle(), ptr) })
+}
+
+fn print(config: &ffi::FT_60XCONFIGURATION) {
+    let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+    let flags = config.OptionalFeatureSupport;
+
+    println!(
+        "VID/PID:        {:04X}:{:04X}",
+        config.VendorID, config.ProductID
+    );
+    println!(
+        "Channels:       {}",
+        CHANNEL_CONFIG_NAMES
+            .get(usize::from(config.ChannelConfig))
+            .unwrap_or(&"unknown")
+    );
+    println!(
+        "FIFO mode:      {}",
+        match config.FIFOMode {
+            FIFO_MODE_245 => "245",
+            FIFO_MODE_600 => "600",
+            _ => "unknown",
+        }
+    );
+    println!(
+        "FIFO clock:     {}",
+        match config.FIFOClock {
+            0 => "100 MHz",
+            1 => "66 MHz",
+            _ => "unknown",
+        }
+    );
+    for pipe in 0..NUM_IN_PIPES {
+        println!(
+            "IN pipe {pipe}:      notify {}, underrun cancels session {}",
+            on_off(flags & (FLAG_NOTIFICATION_ENABLE_PIPE0 << pipe) != 0),
+            on_off(flags & (FLAG_UNDERRUN_DISABLE_PIPE0 << pipe) == 0),
+        );
+    }
+}
+
+/// Apply a single `key=value` setting to the configuration.
+fn apply_setting(config: &mut ffi::FT_60XCONFIGURATION, setting: &str) -> Result<()> {
+    let (key, value) = setting
+        .split_once('=')
+        .ok_or_else(|| format!("expected key=value, got '{setting}'"))?;
+    match key {
+        "channels" => {
+            let index = CHANNEL_CONFIG_NAMES
+                .iter()
+                .position(|name| *name == value)
+                .ok_or_else(|| format!("channels must be one of {CHANNEL_CONFIG_NAMES:?}"))?;
+            config.ChannelConfig = index as u8;
+        }
+        "fifo" => {
+            config.FIFOMode = match value {
+                "245" => FIFO_MODE_245,
+                "600" => FIFO_MODE_600,
+                _ => return Err("fifo must be 245 or 600".into()),
+            };
+        }
+        "clock" => {
+            config.FIFOClock = match value {
+                "100" => 0,
+                "66" => 1,
+                _ => return Err("clock must be 100 or 66".into()),
+            };
+        }
+        "notify" => {
+            for pipe in 0..NUM_IN_PIPES {
+                set_flag(
+                    config,
+                    FLAG_NOTIFICATION_ENABLE_PIPE0 << pipe,
+                    parse_on_off(value)?,
+                );
+            }
+        }
+        _ => {
+            // Per-pipe form: notify0..notify3
+            let pipe = key
+                .strip_prefix("notify")
+                .and_then(|n| n.parse::<u16>().ok())
+                .filter(|n| *n < NUM_IN_PIPES)
+                .ok_or_else(|| format!("unknown config setting '{key}'"))?;
+            set_flag(
+                config,
+                FLAG_NOTIFICATION_ENABLE_PIPE0 << pipe,
+                parse_on_off(value)?,
+            );
+        }
+    }
+    Ok(())
+}
+
+fn set_flag(config: &mut ffi::FT_60XCONFIGURATION, flag: u16, enabled: bool) {
+    if enabled {
+        config.OptionalFeatureSupport |= flag;
+    } else {
+        config.OptionalFeatureSupport &= !flag;
+    }
+}
+
+fn parse_on_off(value: &str) -> Result<bool> {
+    match value {
+        "on" | "1" => Ok(true),
+        "off" | "0" => Ok(false),
+        _ => Err(format!("expected on/off, got '{value}'").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zeroed() -> ffi::FT_60XCONFIGURATION {
+        // SAFETY: the configuration is plain integers, for which all zeroes is valid.
+        unsafe { std::mem::zeroed() }
+    }
+
+    fn apply(config: &mut ffi::FT_60XCONFIGURATION, setting: &str) {
+        if let Err(e) = apply_setting(config, setting) {
+            panic!("{setting}: {e}");
+        }
+    }
+
+    #[test]
+    fn channels_index_mapping() {
+        let mut config = zeroed();
+        for (index, name) in ["4", "2", "1", "1out", "1in"].iter().enumerate() {
+            apply(&mut config, &format!("channels={name}"));
+            assert_eq!(usize::from(config.ChannelConfig), index);
+        }
+    }
+
+    #[test]
+    fn fifo_and_clock() {
+        let mut config = zeroed();
+        apply(&mut config, "fifo=600");
+        apply(&mut config, "clock=66");
+        assert_eq!((config.FIFOMode, config.FIFOClock), (FIFO_MODE_600, 1));
+        apply(&mut config, "fifo=245");
+        apply(&mut config, "clock=100");
+        assert_eq!((config.FIFOMode, config.FIFOClock), (FIFO_MODE_245, 0));
+    }
+
+    #[test]
+    fn notify_all_pipes_leaves_other_bits() {
+        let mut config = zeroed();
+        // Underrun-disable bits for all IN pipes, plus bits outside both ranges.
+        let others = 0b11_1100_0000 | 0b1100_0000_0000 | 0b11;
+        config.OptionalFeatureSupport = others;
+        apply(&mut config, "notify=on");
+        assert_eq!(config.OptionalFeatureSupport, others | 0b11_1100);
+        apply(&mut config, "notify=off");
+        assert_eq!(config.OptionalFeatureSupport, others);
+    }
+
+    #[test]
+    fn notify_single_pipe() {
+        let mut config = zeroed();
+        apply(&mut config, "notify2=1");
+        assert_eq!(config.OptionalFeatureSupport, 0b1_0000);
+        apply(&mut config, "notify0=on");
+        assert_eq!(config.OptionalFeatureSupport, 0b1_0100);
+        apply(&mut config, "notify2=0");
+        assert_eq!(config.OptionalFeatureSupport, 0b0_0100);
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_values() {
+        for setting in [
+            "channels=3",
+            "fifo=601",
+            "clock=50",
+            "notify=yes",
+            "notify4=on",
+            "notifyx=on",
+            "speed=fast",
+            "channels",
+        ] {
+            let mut config = zeroed();
+            config.OptionalFeatureSupport = 0x1234;
+            assert!(
+                apply_setting(&mut config, setting).is_err(),
+                "{setting} should be rejected"
+            );
+            assert_eq!(config.OptionalFeatureSupport, 0x1234);
+        }
+    }
+
+    #[test]
+    fn fifo_245_needs_single_channel() {
+        let mut config = zeroed();
+        apply(&mut config, "fifo=245");
+        for (channels, ok) in [
+            ("4", false),
+            ("2", false),
+            ("1", true),
+            ("1out", true),
+            ("1in", true),
+        ] {
+            apply(&mut config, &format!("channels={channels}"));
+            assert_eq!(validate(&config).is_ok(), ok, "channels={channels}");
+        }
+        apply(&mut config, "fifo=600");
+        apply(&mut config, "channels=4");
+        assert!(validate(&config).is_ok());
+    }
+}
@@ -1,25 +1,188 @@
 use std::io::{Read, Write};
-use d3xx::{list_devices, Pipe};
+use d3xx::{list_devices, Device, Pipe};
 
+use buffer::BufferPool;
+
+use std::path::Path;
+use std::str::FromStr;
 use std::time::Instant;
 
+mod buffer;
+mod capture;
+mod config;
+mod script;
+mod stats;
+mod watchdog;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const USAGE: &str = "\
+Usage: my_d3xx_project [--serial SERIAL] [COMMAND]
+
+Commands:
+  loopback                  Request and read back data from the FPGA (default)
+  capture [OPTIONS]         Stream data from the FPGA, see below
+  script FILE               Run a command sequence file (format in src/script.rs,
+                            example in scripts/)
+  list                      List connected FT60x devices
+  config show               Print the FT60x chip configuration
+  config set KEY=VALUE...   Modify the chip configuration. Keys:
+                              channels=4|2|1|1out|1in
+                              fifo=245|600
+                              clock=100|66
+                              notify=on|off, notify0..notify3=on|off
+  config reset              Restore the factory default configuration
+
+Options:
+  --serial SERIAL           Open the device with this serial number instead of the first one.
+                            capture accepts several (repeated or comma-separated) and
+                            captures from all of them in parallel with a shared start.
+
+Capture options:
+  --bytes N                 Bytes to request from the FPGA per request (default 1000000000)
+  --requests N              Number of requests to make (default 1)
+  --forever                 Keep making requests until interrupted
+  --chunk N                 Maximum bytes per pipe read (default 16777216)
+  --buffers N               Number of chunk buffers in flight to the writer (default 8)
+  --in-pipe N[,N...]        IN pipes (0-3) to read, concurrently if several (default 0)
+  --out-pipe N[,N...]       OUT pipes to send requests on, a different one per IN pipe
+                            (default: the OUT pipe of each IN pipe's channel)
+  -o, --output FILE         Write the received data to FILE, or to stdout if FILE is -
+                            (capture always logs to stderr). With several devices or IN
+                            pipes, {serial} and {pipe} in FILE are replaced by the serial
+                            number and pipe name (in0..in3); any not used are appended to
+                            the file name instead, e.g. run_SERIAL_in1.bin.
+  --reconnect               On a device error, re-open the device by serial and resume.
+                            A 24-byte gap marker (\"FT60xGAP\", u64 LE ms timestamp,
+                            u64 LE bytes captured so far) is written where data was lost.
+  --reconnect-attempts N    Give up after N reconnect attempts, 1 s apart (default 30)
+  --stats                   Report 16-bit sample min/max/mean and stuck bits every 10 s,
+                            plus the byte histogram at the end
+  --stats-interval SECONDS  As --stats, reporting at the given interval
+  --watchdog SECONDS        Log a stall, with bytes captured so far, when a stream
+                            receives nothing for this long. The IN pipe timeout is
+                            set a second longer, and a read timeout is a stall too
+  --stall-abort             Abort the IN pipe on a stall instead of waiting for the
+                            driver timeout
+  --on-stall exit|continue  Once a stall ends the transfer, stop, keeping what
+                            was captured (default), or write a gap marker and re-request";
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let mut serials = Vec::new();
+    let mut rest = args;
+    while let Some(arg) = rest.first() {
+        match arg.as_str() {
+            "--serial" => {
+                let value = rest.get(1).ok_or("--serial needs a value")?;
+                serials.extend(value.split(',').map(str::to_string));
+                rest = &rest[2..];
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => break,
+        }
+    }
+
+    // Only capture can work with several devices at once.
+    let serial = match serials.as_slice() {
+        [] => None,
+        [serial] => Some(serial.as_str()),
+        _ if rest.first().is_some_and(|command| command == "capture") => None,
+        _ => return Err("only the capture command accepts more than one --serial".into()),
+    };
+
+    match rest.first().map(String::as_str) {
+        None | Some("loopback") => loopback(&open_device(serial)?),
+        Some("capture") => {
+            let options = capture::CaptureOptions::parse(&rest[1..])?;
+            if serials.is_empty() {
+                serials.push(device_serial(None)?);
+            }
+            capture::run(&serials, &options)
+        }
+        Some("script") => {
+            let path = rest.get(1).ok_or("script needs a file name")?;
+            script::run(&open_device(serial)?, Path::new(path))
+        }
+        Some("list") => list(),
+        Some("config") => config::run(&open_device(serial)?, &rest[1..]),
+        Some(other) => Err(format!("unknown command '{other}'\n\n{USAGE}").into()),
+    }
+}
 
-    // Scan for connected devices.
-    let all_devices = list_devices().expect("failed to list devices");
+/// Parse the value following a command-line flag.
+pub fn parse_value<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = value.ok_or_else(|| format!("{flag} needs a value"))?;
+    value
+        .parse()
+        .map_err(|e| format!("invalid value '{value}' for {flag}: {e}").into())
+}
+
+/// The given serial number, or that of the first device found.
+///
+/// Commands that may need to re-open the device work with the serial rather than a handle.
+fn device_serial(serial: Option<&str>) -> Result<String> {
+    match serial {
+        Some(serial) => Ok(serial.to_string()),
+        None => {
+            // Scan for connected devices.
+            let all_devices = list_devices()?;
+            let info = all_devices.first().ok_or("no FT60x devices found")?;
+            Ok(info.serial_number().to_string())
+        }
+    }
+}
+
+/// Open the device with the given serial number, or the first one found.
+fn open_device(serial: Option<&str>) -> Result<Device> {
+    Ok(Device::open(&device_serial(serial)?)?)
+}
+
+fn list() -> Result<()> {
+    let all_devices = list_devices()?;
+    if all_devices.is_empty() {
+        println!("No FT60x devices found.");
+    }
+    for info in &all_devices {
+        println!(
+            "{:<16} {:<32} {:04X}:{:04X} {}",
+            info.serial_number(),
+            info.description(),
+            info.vid(),
+            info.pid(),
+            if info.is_open() { "(open)" } else { "" }
+        );
+    }
+    Ok(())
+}
 
-    // Open the first device found.
-    let device = all_devices[0].open().expect("failed to open device");
+fn loopback(device: &Device) -> Result<()> {
+    const PREVIEW_LEN: usize = 16;
+    // One chunk buffer, reused for every read of every iteration.
+    let pool = BufferPool::new(1, 65536*1000);
 
     for _read_iteration in 1..3 {
 
     // Convert to big-endian byte array
-    let num_bytes_to_read: u32 = 1000_000_000;
+    let num_bytes_to_read: u32 = 1_000_000_000;
     let endian_bytes = num_bytes_to_read.to_le_bytes();
     let data_to_write: [u8; 4] = endian_bytes;
     println!("\nAttempting to write 4 bytes: {:?}", data_to_write);
 
-    let bytes_written = device.pipe(Pipe::Out0).write(&data_to_write).unwrap();
+    let bytes_written = device.pipe(Pipe::Out0).write(&data_to_write)?;
 
     // The write_pipe function returns the number of bytes successfully written.
     if bytes_written == data_to_write.len() {
@@ -33,11 +196,13 @@ fn main() {
         );
     }
 
-    // --- Step 6: Read 10,000,000 Bytes in Chunks ---
-    const TOTAL_BYTES_TO_READ: usize = 1000_000_000;
-    // Using a large buffer on the stack can cause a stack overflow.
-    // It's safer to allocate on the heap with a Vec and read in manageable chunks.
-    let mut read_buffer = Vec::with_capacity(TOTAL_BYTES_TO_READ);
+    // --- Step 6: Read 1,000,000,000 Bytes in Chunks ---
+    const TOTAL_BYTES_TO_READ: usize = 1_000_000_000;
+    // Using a large buffer on the stack can cause a stack overflow, so chunks are read into
+    // a reusable page-aligned heap buffer instead. Only a small preview is kept, there is no
+    // need to hold on to the whole transfer (or copy it) just to print its first bytes.
+    let mut chunk = pool.take();
+    let mut preview = Vec::with_capacity(PREVIEW_LEN);
     let mut total_bytes_read = 0;
     println!("Attempting to read {} bytes...", TOTAL_BYTES_TO_READ);
     let start = Instant::now(); // Start the timer
@@ -46,20 +211,19 @@ fn main() {
     // The d3xx driver itself handles chunking at a lower level, but this application-level
     // loop ensures we get the total amount we expect.
     while total_bytes_read < TOTAL_BYTES_TO_READ {
-        // Create a temporary buffer for the next chunk of data.
-        // We try to read up to the remaining amount, with a reasonable max chunk size (e.g. 64KB).
-        let chunk_size = std::cmp::min(65536*1000, TOTAL_BYTES_TO_READ - total_bytes_read);
-        let mut chunk = vec![0; chunk_size];
+        // We try to read up to the remaining amount, with a reasonable max chunk size (e.g. 64MB).
+        let chunk_size = std::cmp::min(chunk.len(), TOTAL_BYTES_TO_READ - total_bytes_read);
 
-        match device.pipe(Pipe::In0).read(&mut chunk) {
+        match device.pipe(Pipe::In0).read(&mut chunk[..chunk_size]) {
             Ok(bytes_in_chunk) => {
                 if bytes_in_chunk == 0 {
                     // This typically means the read timed out. The device may have no more data.
                     println!("\nRead operation finished early (timeout or end of data).");
                     break;
                 }
-                // Add the read bytes to our main buffer.
-                read_buffer.extend_from_slice(&chunk[..bytes_in_chunk]);
+                // Keep the start of the data for the preview below.
+                let preview_missing = PREVIEW_LEN - preview.len();
+                preview.extend_from_slice(&chunk[..std::cmp::min(preview_missing, bytes_in_chunk)]);
                 total_bytes_read += bytes_in_chunk;
             }
             Err(e) => {
@@ -70,6 +234,7 @@ fn main() {
             }
         };
     }
+    pool.give(chunk);
     println!("Total bytes read: {}", total_bytes_read);
     let duration = start.elapsed(); // Get the elapsed time
     println!("Time taken: {:?}, {}ms, {} MB/s", duration, duration.as_millis(), 1000000.0/(duration.as_millis() as f32)); // Print the duration
@@ -77,10 +242,10 @@ fn main() {
     // It's often useful to print a small portion of the read data to verify it.
     if total_bytes_read > 0 {
         // We'll print the first 16 bytes, or fewer if we didn't read that many.
-        let preview_len = std::cmp::min(total_bytes_read, 16);
+        let preview_len = std::cmp::min(total_bytes_read, PREVIEW_LEN);
         println!("Data preview (first {} bytes):", preview_len);
         // Format the output as hex values.
-        for (i, byte) in read_buffer.iter().take(preview_len).enumerate() {
+        for (i, byte) in preview.iter().take(preview_len).enumerate() {
             print!("{:02X} ", byte);
             if (i + 1) % 8 == 0 {
                 println!(); // Newline every 8 bytes for readability
@@ -93,4 +258,5 @@ fn main() {
     } // for loop
 
     println!("\nDemonstration complete.");
+    Ok(())
 }
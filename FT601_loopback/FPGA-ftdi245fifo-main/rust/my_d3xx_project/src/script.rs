@@ -0,0 +1,456 @@
+// Scripted command sequences for unattended FIFO regression tests.
+//
+// A script is a text file with one command per line; `#` starts a comment:
+//
+//   pipe in 0              # select the IN or OUT pipe used by later commands
+//   pipe out 0
+//   write 01 02 03 04      # write these hex bytes to the OUT pipe
+//   request 1000000        # write a byte count as 4-byte little-endian (tx_specified_len)
+//   read 1000000           # read exactly this many bytes from the IN pipe
+//   verify countdown       # check the last read against a pattern, see `Check`
+//   wait 100ms             # sleep, units us, ms or s
+//   repeat 10              # repeat the enclosed commands; blocks may nest
+//     ...
+//   end
+//
+// Any failed step (short read, verify mismatch, device error) stops the script with an error
+// naming the line and repeat iteration, so the exit status can drive a test harness.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use d3xx::{Device, Pipe};
+
+use crate::Result;
+use crate::capture::{in_pipe, out_pipe};
+
+enum Step {
+    Pipe(Pipe),
+    Write(Vec<u8>),
+    Read(usize),
+    Verify(Check),
+    Wait(Duration),
+    Repeat(u64, Vec<Line>),
+}
+
+/// Patterns the last read can be checked against.
+enum Check {
+    /// Same bytes as the last write, for the loopback design.
+    Echo,
+    /// Byte `i` of an `n` byte read is `(n - 1 - i) mod 256`, as sent by tx_specified_len.
+    Countdown,
+    /// The read starts with these bytes.
+    Bytes(Vec<u8>),
+    /// The read equals the contents of this file, given relative to the script.
+    File(PathBuf),
+}
+
+/// A step and the script line it came from.
+struct Line {
+    number: usize,
+    step: Step,
+}
+
+/// State carried from one step to the next.
+struct Context<'a> {
+    device: &'a Device,
+    in_pipe: Pipe,
+    out_pipe: Pipe,
+    last_write: Vec<u8>,
+    last_read: Vec<u8>,
+    bytes_written: u64,
+    bytes_read: u64,
+    /// Current 1-based iteration of each enclosing repeat, for error messages.
+    iterations: Vec<u64>,
+}
+
+pub fn run(device: &Device, path: &Path) -> Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    let name = path.display();
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let steps = parse(&text, dir).map_err(|(line, e)| format!("{name}:{line}: {e}"))?;
+
+    let mut context = Context {
+        device,
+        in_pipe: Pipe::In0,
+        out_pipe: Pipe::Out0,
+        last_write: Vec::new(),
+        last_read: Vec::new(),
+        bytes_written: 0,
+        bytes_read: 0,
+        iterations: Vec::new(),
+    };
+    let start = Instant::now();
+    execute(&steps, &mut context).map_err(|(line, e)| {
+        let mut message = format!("{name}:{line}: {e}");
+        if !context.iterations.is_empty() {
+            let iterations: Vec<_> = context.iterations.iter().map(u64::to_string).collect();
+            message.push_str(&format!(" (repeat iteration {})", iterations.join("/")));
+        }
+        message
+    })?;
+
+    println!(
+        "Script passed: {} bytes written, {} bytes read in {:?}",
+        context.bytes_written,
+        context.bytes_read,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+/// Parse a whole script, resolving file paths against `dir`. Errors carry the 1-based line
+/// number.
+fn parse(text: &str, dir: &Path) -> std::result::Result<Vec<Line>, (usize, String)> {
+    // Stack of open blocks: the repeat count and line that opened it, and the steps so far.
+    let mut blocks: Vec<(u64, usize, Vec<Line>)> = vec![(1, 0, Vec::new())];
+    for (index, raw) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = raw.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            continue;
+        };
+        let args: Vec<&str> = words.collect();
+        let step = match command {
+            "repeat" => {
+                let count = single_arg(&args)
+                    .and_then(parse_number)
+                    .map_err(|e| (number, e))?;
+                blocks.push((count, number, Vec::new()));
+                continue;
+            }
+            "end" => {
+                if blocks.len() == 1 {
+                    return Err((number, "'end' without 'repeat'".to_string()));
+                }
+                let (count, opened, steps) = blocks.pop().expect("checked above");
+                Line {
+                    number: opened,
+                    step: Step::Repeat(count, steps),
+                }
+            }
+            _ => Line {
+                number,
+                step: parse_step(command, &args, dir).map_err(|e| (number, e))?,
+            },
+        };
+        blocks
+            .last_mut()
+            .expect("top level is never popped")
+            .2
+            .push(step);
+    }
+    if blocks.len() > 1 {
+        let (_, opened, _) = blocks.pop().expect("checked above");
+        return Err((opened, "'repeat' without 'end'".to_string()));
+    }
+    Ok(blocks.pop().expect("top level is never popped").2)
+}
+
+fn parse_step(command: &str, args: &[&str], dir: &Path) -> std::result::Result<Step, String> {
+    Ok(match command {
+        "pipe" => match args {
+            ["in", index] => Step::Pipe(in_pipe(parse_pipe_index(index)?)),
+            ["out", index] => Step::Pipe(out_pipe(parse_pipe_index(index)?)),
+            _ => return Err("expected 'pipe in N' or 'pipe out N'".to_string()),
+        },
+        "write" => Step::Write(parse_hex(args)?),
+        "request" => {
+            let count: u32 = single_arg(args)?
+                .parse()
+                .map_err(|e| format!("invalid byte count: {e}"))?;
+            Step::Write(count.to_le_bytes().to_vec())
+        }
+        "read" => Step::Read(
+            single_arg(args)?
+                .parse()
+                .map_err(|e| format!("invalid byte count: {e}"))?,
+        ),
+        "verify" => Step::Verify(match args {
+            ["echo"] => Check::Echo,
+            ["countdown"] => Check::Countdown,
+            ["bytes", bytes @ ..] => Check::Bytes(parse_hex(bytes)?),
+            ["file", path] => Check::File(dir.join(path)),
+            _ => return Err("expected 'verify echo|countdown|bytes HEX...|file PATH'".to_string()),
+        }),
+        "wait" => Step::Wait(parse_duration(single_arg(args)?)?),
+        other => return Err(format!("unknown command '{other}'")),
+    })
+}
+
+fn single_arg<'a>(args: &[&'a str]) -> std::result::Result<&'a str, String> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err(format!("expected one argument, got {}", args.len())),
+    }
+}
+
+fn parse_number(value: &str) -> std::result::Result<u64, String> {
+    value
+        .parse()
+        .map_err(|e| format!("invalid number '{value}': {e}"))
+}
+
+fn parse_pipe_index(value: &str) -> std::result::Result<u8, String> {
+    match value.parse() {
+        Ok(index) if index < 4 => Ok(index),
+        _ => Err(format!("pipe number must be 0-3, got '{value}'")),
+    }
+}
+
+fn parse_hex(args: &[&str]) -> std::result::Result<Vec<u8>, String> {
+    if args.is_empty() {
+        return Err("expected hex bytes".to_string());
+    }
+    args.iter()
+        .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| format!("invalid hex byte '{byte}'")))
+        .collect()
+}
+
+fn parse_duration(value: &str) -> std::result::Result<Duration, String> {
+    let (number, unit) = value.split_at(
+        value
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(value.len()),
+    );
+    let number = parse_number(number)?;
+    match unit {
+        "us" => Ok(Duration::from_micros(number)),
+        "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        _ => Err(format!("expected a duration like 100ms, got '{value}'")),
+    }
+}
+
+/// Run steps in order. Errors carry the line number of the failing step.
+fn execute(steps: &[Line], context: &mut Context) -> std::result::Result<(), (usize, String)> {
+    for line in steps {
+        match &line.step {
+            Step::Repeat(count, body) => {
+                context.iterations.push(0);
+                for iteration in 0..*count {
+                    *context.iterations.last_mut().expect("pushed above") = iteration + 1;
+                    execute(body, context)?;
+                }
+                context.iterations.pop();
+            }
+            step => execute_step(step, context).map_err(|e| (line.number, e))?,
+        }
+    }
+    Ok(())
+}
+
+fn execute_step(step: &Step, context: &mut Context) -> std::result::Result<(), String> {
+    match step {
+        Step::Pipe(pipe) if pipe.is_in() => context.in_pipe = *pipe,
+        Step::Pipe(pipe) => context.out_pipe = *pipe,
+        Step::Write(bytes) => {
+            context
+                .device
+                .pipe(context.out_pipe)
+                .write_all(bytes)
+                .map_err(|e| format!("write failed: {e}"))?;
+            context.bytes_written += bytes.len() as u64;
+            context.last_write.clone_from(bytes);
+        }
+        Step::Read(count) => {
+            // The buffer is reused between reads, only growing when a larger read is asked for.
+            context.last_read.resize(*count, 0);
+            let mut total = 0;
+            while total < *count {
+                let n = context
+                    .device
+                    .pipe(context.in_pipe)
+                    .read(&mut context.last_read[total..])
+                    .map_err(|e| format!("read failed after {total} bytes: {e}"))?;
+                if n == 0 {
+                    return Err(format!("read timed out after {total} of {count} bytes"));
+                }
+                total += n;
+            }
+            context.bytes_read += total as u64;
+        }
+        Step::Verify(check) => verify(check, &context.last_read, &context.last_write)?,
+        Step::Wait(duration) => std::thread::sleep(*duration),
+        Step::Repeat(..) => unreachable!("handled by execute"),
+    }
+    Ok(())
+}
+
+fn verify(check: &Check, data: &[u8], last_write: &[u8]) -> std::result::Result<(), String> {
+    match check {
+        Check::Echo => compare(data, last_write.len(), |i| last_write[i]),
+        // Generated on the fly, reads can be far too large to build a second copy.
+        Check::Countdown => compare(data, data.len(), |i| (data.len() - 1 - i) as u8),
+        Check::Bytes(bytes) => {
+            if data.len() < bytes.len() {
+                return Err(format!(
+                    "verify: read {} bytes, expected at least {}",
+                    data.len(),
+                    bytes.len()
+                ));
+            }
+            compare(&data[..bytes.len()], bytes.len(), |i| bytes[i])
+        }
+        Check::File(path) => {
+            let expected =
+                std::fs::read(path).map_err(|e| format!("verify: {}: {e}", path.display()))?;
+            compare(data, expected.len(), |i| expected[i])
+        }
+    }
+}
+
+/// Compare `data` against `expected_len` bytes given by `expected(offset)`.
+fn compare(
+    data: &[u8],
+    expected_len: usize,
+    expected: impl Fn(usize) -> u8,
+) -> std::result::Result<(), String> {
+    if data.len() != expected_len {
+        return Err(format!(
+            "verify: read {} bytes, expected {expected_len}",
+            data.len()
+        ));
+    }
+    let mut mismatches = (0..data.len()).filter(|&i| data[i] != expected(i));
+    match mismatches.next() {
+        None => Ok(()),
+        Some(offset) => Err(format!(
+            "verify failed at offset {offset}: got {:02X}, expected {:02X} ({} bytes differ)",
+            data[offset],
+            expected(offset),
+            1 + mismatches.count()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_ok(text: &str) -> Vec<Line> {
+        match parse(text, Path::new("")) {
+            Ok(steps) => steps,
+            Err((line, e)) => panic!("line {line}: {e}"),
+        }
+    }
+
+    fn parse_err(text: &str) -> (usize, String) {
+        match parse(text, Path::new("")) {
+            Ok(_) => panic!("script should not parse"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn nested_repeat_blocks() {
+        let steps =
+            parse_ok("repeat 2\n  # comment\n  repeat 3\n\n    read 4\n  end\nend\nwait 1ms\n");
+        assert_eq!(steps.len(), 2);
+        let Step::Repeat(2, outer) = &steps[0].step else {
+            panic!("expected repeat 2");
+        };
+        assert_eq!(steps[0].number, 1);
+        let Step::Repeat(3, inner) = &outer[0].step else {
+            panic!("expected repeat 3");
+        };
+        assert_eq!(outer[0].number, 3);
+        assert!(matches!(inner[0].step, Step::Read(4)));
+        assert_eq!(inner[0].number, 5);
+        assert_eq!(steps[1].number, 8);
+    }
+
+    #[test]
+    fn unmatched_blocks_report_line() {
+        assert_eq!(
+            parse_err("read 1\nrepeat 2\n  read 1\n"),
+            (2, "'repeat' without 'end'".to_string())
+        );
+        assert_eq!(
+            parse_err("repeat 2\nend\nend\n"),
+            (3, "'end' without 'repeat'".to_string())
+        );
+        assert_eq!(
+            parse_err("repeat 2\n  bogus\nend\n"),
+            (2, "unknown command 'bogus'".to_string())
+        );
+    }
+
+    #[test]
+    fn request_is_little_endian_count() {
+        let steps = parse_ok("request 1000000");
+        let Step::Write(bytes) = &steps[0].step else {
+            panic!("expected write");
+        };
+        assert_eq!(bytes, &1_000_000u32.to_le_bytes());
+    }
+
+    #[test]
+    fn verify_file_is_relative_to_script() {
+        let steps = parse(
+            "verify file expected.bin\nverify file /tmp/abs.bin",
+            Path::new("scripts"),
+        )
+        .unwrap_or_else(|(line, e)| panic!("line {line}: {e}"));
+        let paths: Vec<_> = steps
+            .iter()
+            .map(|line| match &line.step {
+                Step::Verify(Check::File(path)) => path.clone(),
+                _ => panic!("expected verify file"),
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("scripts/expected.bin"),
+                PathBuf::from("/tmp/abs.bin")
+            ]
+        );
+    }
+
+    #[test]
+    fn duration_units() {
+        assert_eq!(parse_duration("250us"), Ok(Duration::from_micros(250)));
+        assert_eq!(parse_duration("100ms"), Ok(Duration::from_millis(100)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        for bad in ["10", "ms", "1.5s", "10min"] {
+            assert!(parse_duration(bad).is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn hex_bytes() {
+        assert_eq!(parse_hex(&["01", "ff", "A0"]), Ok(vec![0x01, 0xFF, 0xA0]));
+        assert!(parse_hex(&[]).is_err());
+        assert_eq!(
+            parse_hex(&["01", "zz"]),
+            Err("invalid hex byte 'zz'".to_string())
+        );
+        assert!(parse_hex(&["100"]).is_err());
+    }
+
+    #[test]
+    fn compare_reports_first_mismatch_and_count() {
+        let expected = [1, 2, 3, 4];
+        assert_eq!(compare(&[1, 2, 3, 4], 4, |i| expected[i]), Ok(()));
+        assert_eq!(
+            compare(&[1, 9, 3, 8], 4, |i| expected[i]),
+            Err("verify failed at offset 1: got 09, expected 02 (2 bytes differ)".to_string())
+        );
+        assert_eq!(
+            compare(&[1, 2], 4, |i| expected[i]),
+            Err("verify: read 2 bytes, expected 4".to_string())
+        );
+    }
+
+    #[test]
+    fn verify_countdown_pattern() {
+        let data: Vec<u8> = (0..300).rev().map(|i: usize| i as u8).collect();
+        assert_eq!(verify(&Check::Countdown, &data, &[]), Ok(()));
+        let mut bad = data.clone();
+        bad[0] ^= 1;
+        assert!(verify(&Check::Countdown, &bad, &[]).is_err());
+    }
+}
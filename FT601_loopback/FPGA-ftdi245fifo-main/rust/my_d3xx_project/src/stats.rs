@@ -0,0 +1,163 @@
+// Streaming statistics over captured data.
+//
+// Meant to catch dead ADC channels and bus wiring faults while the capture is running:
+// a data line that never toggles shows up as a stuck bit, and a missing or overrepresented
+// byte value shows up in the histogram. Samples are decoded as 16-bit little-endian words.
+
+pub struct Stats {
+    byte_histogram: [u64; 256],
+    samples: u64,
+    min: u16,
+    max: u16,
+    sum: u128,
+    /// OR and AND of every sample, for stuck-at-0 and stuck-at-1 detection.
+    bits_seen_high: u16,
+    bits_always_high: u16,
+    /// Low byte of a sample split across two blocks.
+    pending_byte: Option<u8>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            byte_histogram: [0; 256],
+            samples: 0,
+            min: u16::MAX,
+            max: 0,
+            sum: 0,
+            bits_seen_high: 0,
+            bits_always_high: u16::MAX,
+            pending_byte: None,
+        }
+    }
+}
+
+impl Stats {
+    pub fn add(&mut self, mut data: &[u8]) {
+        for &byte in data {
+            self.byte_histogram[usize::from(byte)] += 1;
+        }
+
+        if let Some(low) = self.pending_byte.take() {
+            let Some((&high, rest)) = data.split_first() else {
+                self.pending_byte = Some(low);
+                return;
+            };
+            self.add_sample(u16::from_le_bytes([low, high]));
+            data = rest;
+        }
+        let mut words = data.chunks_exact(2);
+        for word in &mut words {
+            self.add_sample(u16::from_le_bytes([word[0], word[1]]));
+        }
+        self.pending_byte = words.remainder().first().copied();
+    }
+
+    /// Forget a half sample at a gap in the data, it can't be paired with what follows.
+    pub fn gap(&mut self) {
+        self.pending_byte = None;
+    }
+
+    fn add_sample(&mut self, sample: u16) {
+        self.samples += 1;
+        self.min = self.min.min(sample);
+        self.max = self.max.max(sample);
+        self.sum += u128::from(sample);
+        self.bits_seen_high |= sample;
+        self.bits_always_high &= sample;
+    }
+
+    /// One-line summary suitable for periodic progress output.
+    pub fn summary(&self) -> String {
+        if self.samples == 0 {
+            return "no samples yet".to_string();
+        }
+        let missing_values = self.byte_histogram.iter().filter(|&&n| n == 0).count();
+        format!(
+            "{} samples, min {} max {} mean {:.1}, stuck-at-0 bits {:#06x}, stuck-at-1 bits {:#06x}, {} byte values never seen",
+            self.samples,
+            self.min,
+            self.max,
+            self.sum as f64 / self.samples as f64,
+            !self.bits_seen_high,
+            self.bits_always_high,
+            missing_values,
+        )
+    }
+
+    /// Byte histogram as 16 rows of 16 counts.
+    pub fn histogram(&self) -> String {
+        let mut out = String::new();
+        for (row, counts) in self.byte_histogram.chunks(16).enumerate() {
+            out.push_str(&format!("{:02X}:", row * 16));
+            for count in counts {
+                out.push_str(&format!(" {count}"));
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_split_across_blocks() {
+        let mut stats = Stats::default();
+        stats.add(&[0x34, 0x12, 0x78]);
+        assert_eq!(stats.samples, 1);
+        assert_eq!(stats.pending_byte, Some(0x78));
+        stats.add(&[0x56]);
+        assert_eq!(stats.samples, 2);
+        assert_eq!(stats.pending_byte, None);
+        assert_eq!((stats.min, stats.max), (0x1234, 0x5678));
+        assert_eq!(stats.sum, 0x1234 + 0x5678);
+    }
+
+    #[test]
+    fn pending_byte_survives_empty_block() {
+        let mut stats = Stats::default();
+        stats.add(&[0xCD]);
+        stats.add(&[]);
+        stats.add(&[0xAB]);
+        assert_eq!(stats.samples, 1);
+        assert_eq!(stats.max, 0xABCD);
+    }
+
+    #[test]
+    fn gap_drops_pending_byte() {
+        let mut stats = Stats::default();
+        stats.add(&[0x01, 0x00, 0xFF]);
+        stats.gap();
+        stats.add(&[0x02, 0x00]);
+        assert_eq!(stats.samples, 2);
+        assert_eq!((stats.min, stats.max), (0x0001, 0x0002));
+        // Every byte still counts in the histogram, including the dropped one.
+        assert_eq!(stats.byte_histogram[0xFF], 1);
+        assert_eq!(stats.byte_histogram[0x00], 2);
+    }
+
+    #[test]
+    fn stuck_bit_masks() {
+        let mut stats = Stats::default();
+        for sample in [0x8001u16, 0x8003, 0x8005] {
+            stats.add(&sample.to_le_bytes());
+        }
+        assert_eq!(!stats.bits_seen_high, 0x7FF8);
+        assert_eq!(stats.bits_always_high, 0x8001);
+        assert!(
+            stats
+                .summary()
+                .contains("stuck-at-0 bits 0x7ff8, stuck-at-1 bits 0x8001")
+        );
+    }
+
+    #[test]
+    fn summary_without_samples() {
+        let mut stats = Stats::default();
+        stats.add(&[0x42]);
+        assert_eq!(stats.summary(), "no samples yet");
+    }
+}
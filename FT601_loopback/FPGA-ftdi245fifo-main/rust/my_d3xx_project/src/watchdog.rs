@@ -0,0 +1,138 @@
+// Stall detection for a capture stream.
+//
+// A blocking pipe read gives no sign of life until the driver's own timeout, and a timeout
+// discards whatever the read had already received. Instead a watchdog thread watches how long
+// the current IN pipe read has been waiting, logs a stall once it has been quiet for too long
+// and can abort the pipe so the blocked read returns straight away.
+//
+// Only time spent inside a read counts: a reader waiting for a free buffer because the
+// output is slow is not a stalled pipe.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use d3xx::{Device, Pipe, ffi};
+
+struct Shared {
+    label: String,
+    timeout: Duration,
+    start: Instant,
+    /// Start of the read in flight, in ms since `start`.
+    read_start_ms: AtomicU64,
+    /// Whether a read is in flight.
+    busy: AtomicBool,
+    total_bytes: AtomicU64,
+    stalled: AtomicBool,
+    done: AtomicBool,
+    /// Handle (as an address) and pipe to abort on a stall, while a session is running.
+    abort_target: Mutex<Option<(usize, u8)>>,
+}
+
+pub struct Watchdog {
+    shared: Arc<Shared>,
+    thread: JoinHandle<()>,
+}
+
+impl Watchdog {
+    /// Watch for `timeout` without progress; with `abort` the armed pipe is aborted on a stall.
+    pub fn spawn(label: String, timeout: Duration, abort: bool) -> Self {
+        let shared = Arc::new(Shared {
+            label,
+            timeout,
+            start: Instant::now(),
+            read_start_ms: AtomicU64::new(0),
+            busy: AtomicBool::new(false),
+            total_bytes: AtomicU64::new(0),
+            stalled: AtomicBool::new(false),
+            done: AtomicBool::new(false),
+            abort_target: Mutex::new(None),
+        });
+        let thread_shared = Arc::clone(&shared);
+        let thread = std::thread::spawn(move || watch(&thread_shared, abort));
+        Self { shared, thread }
+    }
+
+    /// A read on the IN pipe is starting; quiet time counts from now.
+    pub fn begin(&self) {
+        let shared = &self.shared;
+        shared
+            .read_start_ms
+            .store(shared.start.elapsed().as_millis() as u64, Ordering::Relaxed);
+        shared.busy.store(true, Ordering::Release);
+    }
+
+    /// The read returned, having received `bytes` of data.
+    pub fn end(&self, bytes: usize) {
+        let shared = &self.shared;
+        shared.busy.store(false, Ordering::Relaxed);
+        if bytes == 0 {
+            return;
+        }
+        shared
+            .total_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        if shared.stalled.swap(false, Ordering::Relaxed) {
+            eprintln!("{}Data flowing again after stall.", shared.label);
+        }
+    }
+
+    /// Allow aborting `pipe` of `device` on a stall, until `disarm` is called.
+    pub fn arm(&self, device: &Device, pipe: Pipe) {
+        *self.shared.abort_target.lock().unwrap() =
+            Some((device.handle() as usize, u8::from(pipe)));
+    }
+
+    /// Must be called before the armed device is closed.
+    pub fn disarm(&self) {
+        *self.shared.abort_target.lock().unwrap() = None;
+    }
+
+    /// Whether a stall was detected since data last arrived, clearing the flag.
+    pub fn take_stall(&self) -> bool {
+        self.shared.stalled.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn stop(self) {
+        self.shared.done.store(true, Ordering::Relaxed);
+        self.thread.join().expect("watchdog thread panicked");
+    }
+}
+
+fn watch(shared: &Shared, abort: bool) {
+    let poll = (shared.timeout / 10).clamp(Duration::from_millis(1), Duration::from_millis(100));
+    while !shared.done.load(Ordering::Relaxed) {
+        std::thread::sleep(poll);
+        if !shared.busy.load(Ordering::Acquire) {
+            continue;
+        }
+        let since = Duration::from_millis(shared.read_start_ms.load(Ordering::Relaxed));
+        let quiet = shared.start.elapsed().saturating_sub(since);
+        if quiet < shared.timeout || shared.stalled.load(Ordering::Relaxed) {
+            continue;
+        }
+        shared.stalled.store(true, Ordering::Relaxed);
+        let now_unix_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        eprintln!(
+            "\n{}Stall: no data for {quiet:?} at {now_unix_ms} ms since the Unix epoch, {} bytes captured so far",
+            shared.label,
+            shared.total_bytes.load(Ordering::Relaxed)
+        );
+        if abort {
+            // Holding the lock keeps the device open until the abort has been issued.
+            let target = shared.abort_target.lock().unwrap();
+            if let Some((handle, pipe)) = *target {
+                eprintln!("{}Aborting pipe {pipe:#04X}.", shared.label);
+                // SAFETY: the handle stays valid while armed, and FT_AbortPipe is meant to be
+                // called from another thread to cancel a transfer blocked on the pipe.
+                unsafe {
+                    ffi::FT_AbortPipe(handle as ffi::FT_HANDLE, pipe);
+                }
+            }
+        }
+    }
+}